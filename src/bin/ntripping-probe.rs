@@ -0,0 +1,132 @@
+use std::process;
+use std::time::{Duration, Instant};
+
+use clap::Parser;
+use ntripping::request::{self, Request};
+use ntripping::url;
+use serde_json::json;
+
+const EXIT_CONNECT: i32 = 1;
+const EXIT_BAD_STATUS: i32 = 2;
+const EXIT_TIMEOUT: i32 = 3;
+
+/// Check that an NTRIP caster is reachable and serving data.
+///
+/// Exits with 0 once the first byte of the stream is received, 1 if the
+/// connection failed, 2 if the caster responded with a non-success status
+/// and 3 on timeout. A one-line JSON summary is printed to stdout.
+#[derive(Debug, Parser)]
+#[command(name = "ntripping-probe", version = env!("VERGEN_SEMVER_LIGHTWEIGHT"))]
+struct Cli {
+    /// URL of the NTRIP mountpoint to probe
+    url: String,
+
+    /// Time to wait for the first byte, in seconds
+    #[arg(long, default_value_t = 10)]
+    timeout: u64,
+
+    /// Username credentials
+    #[arg(long)]
+    username: Option<String>,
+
+    /// Password credentials
+    #[arg(long)]
+    password: Option<String>,
+
+    /// Maximum number of HTTP redirects to follow
+    #[arg(long, default_value_t = request::DEFAULT_MAX_REDIRECTS)]
+    max_redirects: u32,
+
+    /// Also send credentials when redirected to another host, as load balancing casters do
+    #[arg(long)]
+    redirect_credentials: bool,
+
+    /// GGA sentence to send in the Ntrip-GGA request header, for mountpoints that wait for a position before sending data
+    #[arg(long, value_name = "SENTENCE")]
+    nmea: Option<String>,
+}
+
+struct Probe {
+    status: Option<u32>,
+    ttfb: Option<Duration>,
+    error: Option<String>,
+    exit_code: i32,
+}
+
+fn probe(opt: &Cli) -> Result<Probe, curl::Error> {
+    let mut request = Request::new(&opt.url);
+    request.username = opt.username.clone();
+    request.password = opt.password.clone();
+    request.max_redirects = opt.max_redirects;
+    request.redirect_credentials = opt.redirect_credentials;
+    if let Some(nmea) = &opt.nmea {
+        request.headers.push(format!("Ntrip-GGA: {nmea}"));
+    }
+
+    let mut curl = request.easy()?;
+    curl.timeout(Duration::from_secs(opt.timeout))?;
+
+    let start = Instant::now();
+    let mut ttfb = None;
+    let mut redirects = url::RedirectPolicy::new(&opt.url);
+    let mut refused = None;
+    let result = {
+        let mut transfer = curl.transfer();
+        transfer.header_function(|header| match redirects.check_header(header) {
+            Ok(()) => true,
+            Err(e) => {
+                refused = Some(e);
+                false
+            }
+        })?;
+        transfer.write_function(|_data| {
+            ttfb = Some(start.elapsed());
+            // one byte is enough, abort the transfer
+            Ok(0)
+        })?;
+        transfer.perform()
+    };
+
+    // casters answering with HTTP/0.9 style "ICY 200 OK" have no status code
+    let status = Some(curl.response_code()?).filter(|&code| code != 0);
+    let bad_status = status.filter(|code| !(200..300).contains(code));
+
+    let (error, exit_code) = match result {
+        _ if refused.is_some() => (refused, EXIT_BAD_STATUS),
+        _ if bad_status.is_some() => (None, EXIT_BAD_STATUS),
+        Err(e) if e.is_write_error() && ttfb.is_some() => (None, 0),
+        Err(e) if e.is_operation_timedout() => (Some(e.to_string()), EXIT_TIMEOUT),
+        Err(e) => (Some(e.to_string()), EXIT_CONNECT),
+        Ok(()) => (Some("connection closed without data".into()), EXIT_CONNECT),
+    };
+
+    Ok(Probe {
+        status,
+        ttfb: ttfb.filter(|_| exit_code == 0),
+        error,
+        exit_code,
+    })
+}
+
+fn main() {
+    let opt = Cli::parse();
+
+    let probe = probe(&opt).unwrap_or_else(|e| Probe {
+        status: None,
+        ttfb: None,
+        error: Some(e.to_string()),
+        exit_code: EXIT_CONNECT,
+    });
+
+    let mut line = json!({
+        "url": url::redact_credentials(&opt.url),
+        "status": probe.status,
+        "ttfb_ms": probe.ttfb.map(|ttfb| ttfb.as_millis() as u64),
+    });
+    if let Some(error) = probe.error {
+        line["error"] = json!(error);
+    }
+    println!("{line}");
+
+    process::exit(probe.exit_code);
+}
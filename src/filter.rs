@@ -0,0 +1,190 @@
+use crate::nmea;
+
+/// Longest NMEA sentence allowed by NMEA 0183, including the trailing CRLF
+pub const MAX_SENTENCE_LEN: usize = 82;
+
+/// A piece of a stream split by [`SentenceFilter`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FilteredItem {
+    /// A complete NMEA sentence with a valid checksum, without the line ending
+    Nmea(String),
+    /// Anything else, e.g. RTCM frames
+    Binary(Vec<u8>),
+}
+
+/// Splits NMEA sentences out of a stream of otherwise binary data.
+///
+/// Casters may interleave NMEA sentences such as CRA acknowledgments with the
+/// corrections. Data is pushed in as it arrives and sentences are recognized
+/// even when they are split across pushes. A candidate sentence that turns
+/// out not to be one (non-printable bytes, too long or a bad checksum) is
+/// passed through as binary data, so no bytes are lost or reordered.
+#[derive(Debug, Default)]
+pub struct SentenceFilter {
+    pending: Vec<u8>,
+}
+
+impl SentenceFilter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Feed the next chunk of the stream, returning the items it completed
+    pub fn push(&mut self, data: &[u8]) -> Vec<FilteredItem> {
+        let mut items = Vec::new();
+        let mut binary = Vec::new();
+        for &byte in data {
+            if self.pending.is_empty() {
+                if byte == b'$' {
+                    self.pending.push(byte);
+                } else {
+                    binary.push(byte);
+                }
+                continue;
+            }
+            match byte {
+                b'\n' => {
+                    self.pending.push(byte);
+                    match sentence(&self.pending) {
+                        Some(line) => {
+                            flush(&mut items, &mut binary);
+                            items.push(FilteredItem::Nmea(line));
+                        }
+                        None => binary.extend_from_slice(&self.pending),
+                    }
+                    self.pending.clear();
+                }
+                b'$' => {
+                    // a new sentence starts, so the pending one was not a sentence
+                    binary.append(&mut self.pending);
+                    self.pending.push(byte);
+                }
+                // leave room for the line feed
+                b' '..=b'~' | b'\r' if self.pending.len() < MAX_SENTENCE_LEN - 1 => {
+                    self.pending.push(byte);
+                }
+                _ => {
+                    binary.append(&mut self.pending);
+                    binary.push(byte);
+                }
+            }
+        }
+        flush(&mut items, &mut binary);
+        items
+    }
+
+    /// Return any partial sentence held back at the end of the stream
+    pub fn finish(&mut self) -> Option<FilteredItem> {
+        if self.pending.is_empty() {
+            None
+        } else {
+            Some(FilteredItem::Binary(std::mem::take(&mut self.pending)))
+        }
+    }
+}
+
+fn flush(items: &mut Vec<FilteredItem>, binary: &mut Vec<u8>) {
+    if !binary.is_empty() {
+        items.push(FilteredItem::Binary(std::mem::take(binary)));
+    }
+}
+
+/// Validate a `$...*HH\r\n` line and return it without the line ending
+fn sentence(line: &[u8]) -> Option<String> {
+    let line = line.strip_suffix(b"\n")?;
+    let line = line.strip_suffix(b"\r").unwrap_or(line);
+    if line.contains(&b'\r') {
+        return None;
+    }
+    let line = std::str::from_utf8(line).ok()?;
+    nmea::validate(line).ok()?;
+    Some(line.to_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ACK: &str = "$PSWTCRA,1,2*53";
+
+    fn nmea(line: &str) -> FilteredItem {
+        FilteredItem::Nmea(line.to_owned())
+    }
+
+    fn binary(bytes: &[u8]) -> FilteredItem {
+        FilteredItem::Binary(bytes.to_vec())
+    }
+
+    #[test]
+    fn sentence_between_binary() {
+        let mut filter = SentenceFilter::new();
+        let data = [b"\xd3\x00".as_ref(), ACK.as_bytes(), b"\r\n\x01"].concat();
+        assert_eq!(
+            filter.push(&data),
+            vec![binary(b"\xd3\x00"), nmea(ACK), binary(b"\x01")]
+        );
+    }
+
+    #[test]
+    fn sentence_split_across_pushes() {
+        let mut filter = SentenceFilter::new();
+        let (a, b) = ACK.split_at(6);
+        assert_eq!(filter.push(a.as_bytes()), vec![]);
+        assert_eq!(filter.push(b.as_bytes()), vec![]);
+        assert_eq!(filter.push(b"\r"), vec![]);
+        assert_eq!(filter.push(b"\n\x01"), vec![nmea(ACK), binary(b"\x01")]);
+    }
+
+    #[test]
+    fn binary_with_dollar() {
+        let mut filter = SentenceFilter::new();
+        assert_eq!(filter.push(b"\x01$abc\x02"), vec![binary(b"\x01$abc\x02")]);
+        // printable bytes up to a line feed that are not a sentence
+        assert_eq!(filter.push(b"$abc*00\n"), vec![binary(b"$abc*00\n")]);
+    }
+
+    #[test]
+    fn bad_checksum_in_order() {
+        let mut filter = SentenceFilter::new();
+        let data = b"\x01$PSWTCRA,1,2*54\r\n\x02";
+        assert_eq!(filter.push(data), vec![binary(data)]);
+
+        let data = [b"$GPbad*00\r\n".as_ref(), ACK.as_bytes(), b"\r\n"].concat();
+        assert_eq!(
+            filter.push(&data),
+            vec![binary(b"$GPbad*00\r\n"), nmea(ACK)]
+        );
+    }
+
+    #[test]
+    fn too_long() {
+        let mut filter = SentenceFilter::new();
+        let body = format!("GP{}", "A".repeat(80));
+        let crc = nmea::checksum(body.as_bytes());
+        let line = format!("${body}*{crc:02X}\r\n");
+        assert_eq!(filter.push(line.as_bytes()), vec![binary(line.as_bytes())]);
+        assert_eq!(filter.finish(), None);
+    }
+
+    #[test]
+    fn longest_sentence() {
+        let mut filter = SentenceFilter::new();
+        // 79 characters plus CRLF is the NMEA maximum of 82
+        let body = format!("GP{}", "A".repeat(MAX_SENTENCE_LEN - 8));
+        let crc = nmea::checksum(body.as_bytes());
+        let line = format!("${body}*{crc:02X}");
+        assert_eq!(line.len() + 2, MAX_SENTENCE_LEN);
+        assert_eq!(
+            filter.push(format!("{line}\r\n").as_bytes()),
+            vec![nmea(&line)]
+        );
+    }
+
+    #[test]
+    fn finish_returns_partial_sentence() {
+        let mut filter = SentenceFilter::new();
+        assert_eq!(filter.push(b"\x01$PSWT"), vec![binary(b"\x01")]);
+        assert_eq!(filter.finish(), Some(binary(b"$PSWT")));
+        assert_eq!(filter.finish(), None);
+    }
+}
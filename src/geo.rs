@@ -0,0 +1,105 @@
+/// WGS-84 semi-major axis, in meters
+pub const WGS84_A: f64 = 6_378_137.0;
+
+/// WGS-84 flattening
+pub const WGS84_F: f64 = 1.0 / 298.257_223_563;
+
+/// WGS-84 first eccentricity squared
+const WGS84_E2: f64 = WGS84_F * (2.0 - WGS84_F);
+
+/// Geodetic latitude, longitude and height above the WGS-84 ellipsoid
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Llh {
+    pub lat_deg: f64,
+    pub lon_deg: f64,
+    pub height_m: f64,
+}
+
+/// Earth-centered, earth-fixed coordinates, in meters
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Ecef {
+    pub x: f64,
+    pub y: f64,
+    pub z: f64,
+}
+
+impl Llh {
+    /// Straight line distance to `other` through the ellipsoid, in meters
+    pub fn distance_to(&self, other: &Llh) -> f64 {
+        let (a, b) = (Ecef::from(*self), Ecef::from(*other));
+        ((a.x - b.x).powi(2) + (a.y - b.y).powi(2) + (a.z - b.z).powi(2)).sqrt()
+    }
+}
+
+impl From<Llh> for Ecef {
+    fn from(llh: Llh) -> Self {
+        let (sin_lat, cos_lat) = llh.lat_deg.to_radians().sin_cos();
+        let (sin_lon, cos_lon) = llh.lon_deg.to_radians().sin_cos();
+        // prime vertical radius of curvature
+        let n = WGS84_A / (1.0 - WGS84_E2 * sin_lat * sin_lat).sqrt();
+        Ecef {
+            x: (n + llh.height_m) * cos_lat * cos_lon,
+            y: (n + llh.height_m) * cos_lat * sin_lon,
+            z: (n * (1.0 - WGS84_E2) + llh.height_m) * sin_lat,
+        }
+    }
+}
+
+impl From<Ecef> for Llh {
+    fn from(ecef: Ecef) -> Self {
+        let p = ecef.x.hypot(ecef.y);
+        let lon = ecef.y.atan2(ecef.x);
+
+        // fixed point iteration on latitude, converges to well below a
+        // millimeter within a handful of steps for terrestrial positions
+        let mut lat = ecef.z.atan2(p * (1.0 - WGS84_E2));
+        let mut height = 0.0;
+        for _ in 0..10 {
+            let (sin_lat, cos_lat) = lat.sin_cos();
+            let n = WGS84_A / (1.0 - WGS84_E2 * sin_lat * sin_lat).sqrt();
+            // valid at the poles, unlike p / cos(lat) - n
+            height = p * cos_lat + ecef.z * sin_lat - WGS84_A * WGS84_A / n;
+            let next = ecef.z.atan2(p * (1.0 - WGS84_E2 * n / (n + height)));
+            if (next - lat).abs() < 1e-14 {
+                lat = next;
+                break;
+            }
+            lat = next;
+        }
+
+        Llh {
+            lat_deg: lat.to_degrees(),
+            lon_deg: lon.to_degrees(),
+            height_m: height,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn distance(a: Ecef, b: Ecef) -> f64 {
+        ((a.x - b.x).powi(2) + (a.y - b.y).powi(2) + (a.z - b.z).powi(2)).sqrt()
+    }
+
+    #[test]
+    fn round_trip_within_a_millimeter() {
+        let lats = (-18..=18).map(|i| f64::from(i) * 5.0);
+        for lat_deg in lats.chain([-89.999999, 89.999999]) {
+            for lon_deg in (-12..=12).map(|i| f64::from(i) * 15.0) {
+                for height_m in [-1_000.0, 0.0, 100.0, 5_000.0, 20_000.0] {
+                    let llh = Llh {
+                        lat_deg,
+                        lon_deg,
+                        height_m,
+                    };
+                    let ecef = Ecef::from(llh);
+                    let back = Ecef::from(Llh::from(ecef));
+                    let error = distance(ecef, back);
+                    assert!(error < 1e-3, "{:?} off by {} m", llh, error);
+                }
+            }
+        }
+    }
+}
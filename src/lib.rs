@@ -0,0 +1,8 @@
+pub mod filter;
+pub mod geo;
+pub mod nmea;
+pub mod request;
+pub mod rtcm3;
+pub mod sourcetable;
+pub mod url;
+pub mod version;
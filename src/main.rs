@@ -1,17 +1,40 @@
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
 use std::fmt::Write;
-use std::io::{self, Write as _};
+use std::fs;
+use std::io::{self, BufRead, IsTerminal, Read, Write as _};
 use std::iter;
 use std::path::PathBuf;
+use std::process;
 use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
 use std::thread;
-use std::time::{Duration, SystemTime};
+use std::time::{Duration, Instant, SystemTime};
 
 use anyhow::anyhow;
 use chrono::{DateTime, Utc};
-use clap::{ArgGroup, Parser};
-use curl::easy::{Easy, HttpVersion, List, ReadError};
+use clap::{ArgAction, ArgGroup, Args, Parser, Subcommand, ValueEnum};
+use curl::easy::ReadError;
 use flume::TryRecvError;
+use ntripping::filter::{FilteredItem, SentenceFilter};
+use ntripping::geo::Llh;
+use ntripping::nmea;
+use ntripping::request::{self, Request};
+use ntripping::rtcm3::Rtcm3Framer;
+use ntripping::sourcetable::{self, StrRecord};
+use ntripping::url;
+use tracing::level_filters::LevelFilter;
+use tracing_subscriber::fmt;
+use tracing_subscriber::prelude::*;
+
+/// How much of an error response body to report
+const MAX_ERROR_BODY: usize = 1024;
+
+/// Set by the signal handler to end the transfer
+static SHUTDOWN: AtomicBool = AtomicBool::new(false);
+
+/// Set when the thread sending messages fails, to end the transfer
+static SENDER_FAILED: AtomicBool = AtomicBool::new(false);
 
 type Result<T> = std::result::Result<T, Box<dyn std::error::Error + Send + Sync>>;
 
@@ -19,7 +42,23 @@ type Result<T> = std::result::Result<T, Box<dyn std::error::Error + Send + Sync>
 #[command(
     name = "ntripping",
     about = "NTRIP command line client",
-    version = env!("VERGEN_SEMVER_LIGHTWEIGHT"),
+    version = env!("VERGEN_SEMVER_LIGHTWEIGHT")
+)]
+struct Cli {
+    #[command(subcommand)]
+    command: Commands,
+
+    /// Increase logging verbosity, -v for debug and -vv for trace. Any level also enables verbose curl output
+    #[arg(short, long, action = ArgAction::Count, global = true)]
+    verbose: u8,
+
+    /// Also write logs to this file, as JSON lines
+    #[arg(long, global = true)]
+    log_file: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Args)]
+#[command(
     group(
         ArgGroup::new("gga")
             .conflicts_with_all(["input", "cra"])
@@ -33,22 +72,22 @@ type Result<T> = std::result::Result<T, Box<dyn std::error::Error + Send + Sync>
             .multiple(true),
     ),
 )]
-struct Cli {
+struct ConnectArgs {
     /// URL of the NTRIP caster
     #[arg(long, default_value = "na.skylark.swiftnav.com:2101/")]
     url: String,
 
     /// Receiver latitude to report, in degrees
     #[arg(long, default_value_t = 37.77101999622968, allow_hyphen_values = true)]
-    lat: f32,
+    lat: f64,
 
     /// Receiver longitude to report, in degrees
     #[arg(long, default_value_t = -122.40315159140708, allow_hyphen_values = true)]
-    lon: f32,
+    lon: f64,
 
     /// Receiver height to report, in meters
     #[arg(long, default_value_t = -5.549358852471994, allow_hyphen_values = true)]
-    height: f32,
+    height: f64,
 
     /// Client ID
     #[arg(
@@ -58,10 +97,6 @@ struct Cli {
     )]
     client_id: String,
 
-    /// Enable verbose curl output
-    #[arg(short, long)]
-    verbose: bool,
-
     /// Enable curl debug output. This implicitly enables verbose output
     #[arg(short, long)]
     debug: bool,
@@ -78,7 +113,7 @@ struct Cli {
     #[arg(long)]
     password: Option<String>,
 
-    /// NMEA sentence update period, in seconds. 0 means to never send a sentence
+    /// NMEA sentence update period, in seconds. 0 means to never send a sentence, or with --serial-port to forward every GGA sentence
     #[arg(
         long,
         default_value_t = 10,
@@ -126,6 +161,107 @@ struct Cli {
     /// Private key file for mTLS
     #[arg(long, requires = "cert")]
     key: Option<PathBuf>,
+
+    /// Maximum number of HTTP redirects to follow
+    #[arg(long, default_value_t = request::DEFAULT_MAX_REDIRECTS)]
+    max_redirects: u32,
+
+    /// Also send credentials when redirected to another host, as load balancing casters do
+    #[arg(long)]
+    redirect_credentials: bool,
+
+    /// Time to wait for the connection to the caster, in seconds
+    #[arg(long)]
+    connect_timeout: Option<u64>,
+
+    /// Drop the connection when no data is received for this many seconds
+    /// after the caster has started responding
+    #[arg(long)]
+    read_timeout: Option<u64>,
+
+    /// Write the process ID to this file while running
+    #[arg(long)]
+    pid_file: Option<PathBuf>,
+
+    /// Raw NMEA sentence to send once after connecting, before the periodic messages. May be repeated
+    #[arg(long, conflicts_with = "input", value_parser = parse_sentence)]
+    input_sentence: Vec<String>,
+
+    /// Serial port of a GNSS receiver to forward GGA sentences from, at most one per NMEA period, instead of reporting a fixed position. The connection is closed if the port fails
+    #[arg(
+        long,
+        conflicts_with_all = ["input", "gga", "cra", "pos_to_area_id", "nmea_header"]
+    )]
+    serial_port: Option<String>,
+
+    /// Baud rate of the serial port
+    #[arg(long, default_value_t = 9600, requires = "serial_port")]
+    serial_baud: u32,
+
+    /// Print NMEA sentences sent back by the caster, e.g. CRA acknowledgments, to stderr instead of mixing them into the corrections on stdout
+    #[arg(long)]
+    nmea_ack: bool,
+
+    /// What to write to stdout of the data received from the caster
+    #[arg(long, value_enum, default_value_t = OutputFormat::Binary)]
+    output_format: OutputFormat,
+}
+
+#[derive(Debug, Clone, Subcommand)]
+enum Commands {
+    /// Connect to a mountpoint and stream corrections to stdout
+    Connect(Box<ConnectArgs>),
+    /// List the mountpoints in a caster's sourcetable
+    Sourcetable(SourcetableArgs),
+}
+
+#[derive(Debug, Clone, Args)]
+struct SourcetableArgs {
+    /// URL of the NTRIP caster root
+    url: String,
+
+    /// Output format
+    #[arg(long, value_enum, default_value_t = Format::Table)]
+    format: Format,
+
+    /// Only print the mountpoint closest to the given position, in degrees
+    #[arg(
+        long,
+        value_name = "LAT,LON",
+        value_parser = parse_lat_lon,
+        allow_hyphen_values = true
+    )]
+    nearest: Option<(f64, f64)>,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum Format {
+    Table,
+    Json,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum OutputFormat {
+    /// Everything the caster sends, unchanged
+    Binary,
+    /// Only NMEA sentences, one per line
+    Nmea,
+    /// Only RTCM3 frames with a valid CRC
+    Rtcm3,
+}
+
+fn parse_sentence(s: &str) -> std::result::Result<String, String> {
+    nmea::validate(s).map_err(|e| e.to_string())?;
+    Ok(s.to_owned())
+}
+
+fn parse_lat_lon(s: &str) -> std::result::Result<(f64, f64), String> {
+    let (lat, lon) = s
+        .split_once(',')
+        .ok_or_else(|| format!("expected <LAT>,<LON>, got {s:?}"))?;
+    let lat = lat.trim().parse().map_err(|e| format!("latitude: {e}"))?;
+    let lon = lon.trim().parse().map_err(|e| format!("longitude: {e}"))?;
+    Ok((lat, lon))
 }
 
 #[derive(Debug, Clone, Copy, serde::Deserialize)]
@@ -154,26 +290,20 @@ impl std::fmt::Display for Command {
             SystemTime::UNIX_EPOCH + Duration::from_secs(e.into())
         });
         let message = self.message.format(now.into());
-        let checksum = self.crc.unwrap_or_else(|| checksum(message.as_bytes()));
-        write!(f, "{message}*{checksum:X}")
-    }
-}
-
-fn checksum(buf: &[u8]) -> u8 {
-    let mut sum = 0;
-    for c in &buf[1..] {
-        sum ^= c;
+        let checksum = self
+            .crc
+            .unwrap_or_else(|| nmea::checksum(&message.as_bytes()[1..]));
+        write!(f, "{message}*{checksum:02X}")
     }
-    sum
 }
 
 #[derive(Debug, Clone, Copy, serde::Deserialize)]
 #[serde(rename_all = "lowercase")]
 enum Message {
     Gga {
-        lat: f32,
-        lon: f32,
-        height: f32,
+        lat: f64,
+        lon: f64,
+        height: f64,
     },
     Cra {
         request_counter: Option<u8>,
@@ -184,26 +314,32 @@ enum Message {
 }
 
 impl Message {
+    /// The request header used to send this message with `--nmea-header`
+    fn header_name(&self) -> &'static str {
+        match self {
+            Message::Gga { .. } => "Ntrip-GGA",
+            Message::Cra { .. } => "Ntrip-CRA",
+        }
+    }
+
     fn format(&self, time: DateTime<Utc>) -> String {
         match *self {
             Message::Gga { lat, lon, height } => {
-                let time = time.format("%H%M%S.00");
-
-                let latn = ((lat * 1e8).round() / 1e8).abs();
-                let lonn = ((lon * 1e8).round() / 1e8).abs();
-
-                let lat_deg = latn as u16;
-                let lon_deg = lonn as u16;
-
-                let lat_min = (latn - (lat_deg as f32)) * 60.0;
-                let lon_min = (lonn - (lon_deg as f32)) * 60.0;
+                // nanoseconds can exceed 1s during a leap second
+                let centis = (time.timestamp_subsec_nanos() / 10_000_000).min(99);
+                let time = format!("{}.{:02}", time.format("%H%M%S"), centis);
 
                 let lat_dir = if lat < 0.0 { 'S' } else { 'N' };
                 let lon_dir = if lon < 0.0 { 'W' } else { 'E' };
 
                 format!(
-                    "$GPGGA,{},{:02}{:010.7},{},{:03}{:010.7},{},4,12,1.3,{:.2},M,0.0,M,1.7,0078",
-                    time, lat_deg, lat_min, lat_dir, lon_deg, lon_min, lon_dir, height
+                    "$GPGGA,{},{},{},{},{},4,12,1.3,{:.2},M,0.0,M,1.7,0078",
+                    time,
+                    degrees_minutes(lat, 2),
+                    lat_dir,
+                    degrees_minutes(lon, 3),
+                    lon_dir,
+                    height
                 )
             }
             Message::Cra {
@@ -234,7 +370,36 @@ impl Message {
     }
 }
 
-fn build_cra(opt: &Cli) -> Command {
+/// Formats an angle as NMEA degrees and minutes, e.g. `3746.2622667`.
+///
+/// The angle is rounded once, to the last printed digit of the minutes, so
+/// values just below a whole degree carry into the degrees instead of
+/// printing 60 minutes.
+fn degrees_minutes(angle: f64, degree_width: usize) -> String {
+    const UNITS_PER_MINUTE: u64 = 10_000_000;
+    const UNITS_PER_DEGREE: u64 = 60 * UNITS_PER_MINUTE;
+
+    let units = (angle.abs() * UNITS_PER_DEGREE as f64).round() as u64;
+    let degrees = units / UNITS_PER_DEGREE;
+    let minutes = units % UNITS_PER_DEGREE;
+    format!(
+        "{:0width$}{:02}.{:07}",
+        degrees,
+        minutes / UNITS_PER_MINUTE,
+        minutes % UNITS_PER_MINUTE,
+        width = degree_width
+    )
+}
+
+fn build_command(opt: &ConnectArgs) -> Command {
+    if opt.area_id.is_some() || opt.pos_to_area_id {
+        build_cra(opt)
+    } else {
+        build_gga(opt)
+    }
+}
+
+fn build_cra(opt: &ConnectArgs) -> Command {
     Command {
         epoch: opt.epoch,
         after: 0,
@@ -242,7 +407,8 @@ fn build_cra(opt: &Cli) -> Command {
         message: Message::Cra {
             request_counter: opt.request_counter,
             area_id: if opt.pos_to_area_id {
-                Some(area_id(opt.lat, opt.lon))
+                // area IDs have always been computed in single precision
+                Some(area_id(opt.lat as f32, opt.lon as f32))
             } else {
                 opt.area_id
             },
@@ -252,7 +418,7 @@ fn build_cra(opt: &Cli) -> Command {
     }
 }
 
-fn build_gga(opt: &Cli) -> Command {
+fn build_gga(opt: &ConnectArgs) -> Command {
     Command {
         epoch: opt.epoch,
         after: 0,
@@ -319,10 +485,27 @@ fn area_id(lat: f32, lon: f32) -> i32 {
         + params.offset
 }
 
-fn get_commands(opt: Cli) -> Result<Box<dyn Iterator<Item = Command> + Send>> {
+fn check_position(lat: f64, lon: f64) -> Result<()> {
+    if !(-90.0..=90.0).contains(&lat) {
+        return Err(anyhow!("Invalid latitude of {}", lat).into());
+    }
+
+    if !(-180.0..=180.0).contains(&lon) {
+        return Err(anyhow!("Invalid longitude of {}", lon).into());
+    }
+
+    Ok(())
+}
+
+fn get_commands(opt: ConnectArgs) -> Result<Box<dyn Iterator<Item = Command> + Send>> {
     if let Some(path) = opt.input {
         let file = std::fs::File::open(path)?;
-        let cmds: Vec<_> = serde_yaml::from_reader(file)?;
+        let cmds: Vec<Command> = serde_yaml::from_reader(file)?;
+        for cmd in &cmds {
+            if let Message::Gga { lat, lon, .. } = cmd.message {
+                check_position(lat, lon)?;
+            }
+        }
         return Ok(Box::new(cmds.into_iter()));
     }
 
@@ -330,8 +513,8 @@ fn get_commands(opt: Cli) -> Result<Box<dyn Iterator<Item = Command> + Send>> {
         return Ok(Box::new(iter::empty()));
     }
 
-    if opt.area_id.is_some() || opt.pos_to_area_id {
-        let first = build_cra(&opt);
+    let first = build_command(&opt);
+    if let Message::Cra { .. } = first.message {
         let it = iter::successors(Some(first), move |prev| {
             let mut next = *prev;
             if let Message::Cra {
@@ -340,13 +523,15 @@ fn get_commands(opt: Cli) -> Result<Box<dyn Iterator<Item = Command> + Send>> {
             } = &mut next.message
             {
                 *counter = counter.wrapping_add(1);
+                if *counter == 0 {
+                    tracing::debug!("request counter wrapped around to 0");
+                }
             }
             next.after = opt.nmea_period;
             Some(next)
         });
         Ok(Box::new(it))
     } else {
-        let first = build_gga(&opt);
         let rest = iter::repeat(Command {
             after: opt.nmea_period,
             ..first
@@ -355,51 +540,286 @@ fn get_commands(opt: Cli) -> Result<Box<dyn Iterator<Item = Command> + Send>> {
     }
 }
 
-fn run() -> Result<()> {
-    let opt = Cli::parse();
+/// Returns the code of an HTTP status line
+fn status_code(header: &[u8]) -> Option<u32> {
+    let header = std::str::from_utf8(header).ok()?;
+    if !header.starts_with("HTTP/") {
+        return None;
+    }
+    header.split_whitespace().nth(1)?.parse().ok()
+}
 
-    if opt.lat < -90.0 || opt.lat > 90.0 {
-        return Err(anyhow!("Invalid latitude of {}", opt.lat).into());
+fn print_sourcetable(args: SourcetableArgs) -> Result<()> {
+    let table = sourcetable::fetch(&args.url)?;
+    for e in &table.skipped {
+        tracing::warn!("skipped sourcetable record: {e}");
     }
 
-    if opt.lon < -180.0 || opt.lon > 180.0 {
-        return Err(anyhow!("Invalid longitude of {}", opt.lon).into());
+    let mut streams = table.streams;
+    if let Some((lat, lon)) = args.nearest {
+        let here = Llh {
+            lat_deg: lat,
+            lon_deg: lon,
+            height_m: 0.0,
+        };
+        let dist = |s: &StrRecord| {
+            here.distance_to(&Llh {
+                lat_deg: s.lat,
+                lon_deg: s.lon,
+                height_m: 0.0,
+            })
+        };
+        streams = streams
+            .into_iter()
+            .min_by(|a, b| dist(a).total_cmp(&dist(b)))
+            .into_iter()
+            .collect();
     }
 
-    let mut curl = Easy::new();
+    match args.format {
+        Format::Json => println!("{}", serde_json::to_string_pretty(&streams)?),
+        Format::Table => {
+            let width = |header: &str, field: fn(&StrRecord) -> &str| {
+                streams
+                    .iter()
+                    .map(|s| field(s).len())
+                    .chain(iter::once(header.len()))
+                    .max()
+                    .unwrap_or_default()
+            };
+            let mount = width("MOUNTPOINT", |s| &s.mountpoint);
+            let format = width("FORMAT", |s| &s.format);
+            let country = width("COUNTRY", |s| &s.country);
+            println!(
+                "{:mount$}  {:format$}  {:country$}  {:>8}  {:>9}",
+                "MOUNTPOINT", "FORMAT", "COUNTRY", "LAT", "LON"
+            );
+            for s in &streams {
+                println!(
+                    "{:mount$}  {:format$}  {:country$}  {:>8.2}  {:>9.2}",
+                    s.mountpoint, s.format, s.country, s.lat, s.lon
+                );
+            }
+        }
+    }
 
-    let mut headers = List::new();
-    headers.append("Transfer-Encoding:")?;
-    headers.append("Ntrip-Version: Ntrip/2.0")?;
-    headers.append(&format!("X-SwiftNav-Client-Id: {}", opt.client_id))?;
+    Ok(())
+}
 
-    if opt.nmea_header {
-        if opt.area_id.is_some() {
-            headers.append(&format!("Ntrip-CRA: {}", build_cra(&opt)))?;
-        } else {
-            headers.append(&format!("Ntrip-GGA: {}", build_gga(&opt)))?;
+/// Longest line accepted from a receiver, longer ones are discarded
+const MAX_SERIAL_LINE: usize = 1024;
+
+/// Sends the GGA sentences read from a receiver, at most one per `period`
+fn forward_serial_gga(
+    mut reader: impl BufRead,
+    period: Duration,
+    tx: &flume::Sender<Vec<u8>>,
+) -> Result<()> {
+    let mut line = Vec::new();
+    let mut discarding = false;
+    let mut last_sent: Option<Instant> = None;
+    loop {
+        // keep partial lines across read timeouts, up to the limit
+        let limit = (MAX_SERIAL_LINE - line.len()) as u64;
+        match (&mut reader).take(limit).read_until(b'\n', &mut line) {
+            Ok(0) => return Err(anyhow!("serial port closed").into()),
+            Ok(_) => {}
+            Err(e) if e.kind() == io::ErrorKind::TimedOut => continue,
+            Err(e) => return Err(anyhow!("could not read serial port: {e}").into()),
+        }
+        if !line.ends_with(b"\n") {
+            if line.len() >= MAX_SERIAL_LINE {
+                tracing::warn!("discarding line longer than {MAX_SERIAL_LINE} bytes");
+                line.clear();
+                discarding = true;
+            }
+            continue;
+        }
+        if discarding {
+            // the rest of an overlong line
+            discarding = false;
+            line.clear();
+            continue;
+        }
+        let text = String::from_utf8_lossy(&line);
+        let sentence = text.trim();
+        // any talker ID, e.g. $GPGGA or $GNGGA
+        if sentence.starts_with('$') && sentence.get(3..6) == Some("GGA") {
+            match parse_sentence(sentence) {
+                Ok(_) if matches!(last_sent, Some(t) if t.elapsed() < period) => {}
+                Ok(sentence) => {
+                    last_sent = Some(Instant::now());
+                    tracing::debug!("sending {sentence}");
+                    if tx.send(sentence.into_bytes()).is_err() {
+                        return Ok(());
+                    }
+                }
+                Err(e) => tracing::warn!("ignoring {sentence}: {e}"),
+            }
+        }
+        line.clear();
+    }
+}
+
+/// Writes the data received from the caster according to the output format
+struct Output {
+    format: OutputFormat,
+    filter: Option<SentenceFilter>,
+    framer: Option<Rtcm3Framer>,
+}
+
+impl Output {
+    fn new(format: OutputFormat, nmea_ack: bool) -> Self {
+        Output {
+            format,
+            filter: (nmea_ack || format == OutputFormat::Nmea).then(SentenceFilter::new),
+            framer: (format == OutputFormat::Rtcm3).then(Rtcm3Framer::new),
+        }
+    }
+
+    fn write(&mut self, data: &[u8]) -> io::Result<()> {
+        match &mut self.filter {
+            Some(filter) => filter
+                .push(data)
+                .into_iter()
+                .try_for_each(|item| self.write_item(item)),
+            None => self.write_binary(data),
+        }
+    }
+
+    fn write_item(&mut self, item: FilteredItem) -> io::Result<()> {
+        match item {
+            FilteredItem::Nmea(line) if self.format == OutputFormat::Nmea => {
+                writeln!(io::stdout(), "{line}")
+            }
+            FilteredItem::Nmea(line) => writeln!(io::stderr(), "[NMEA ACK] {line}"),
+            FilteredItem::Binary(_) if self.format == OutputFormat::Nmea => Ok(()),
+            FilteredItem::Binary(bytes) => self.write_binary(&bytes),
+        }
+    }
+
+    fn write_binary(&mut self, data: &[u8]) -> io::Result<()> {
+        let framer = match &mut self.framer {
+            Some(framer) => framer,
+            None => return io::stdout().write_all(data),
+        };
+        for frame in framer.push(data) {
+            match frame {
+                Ok(frame) => io::stdout().write_all(&frame)?,
+                // skip the frame but keep the session going
+                Err(e) => tracing::warn!(invalid_frames = framer.invalid_frames(), "{e}"),
+            }
+        }
+        Ok(())
+    }
+
+    /// Write out a partial sentence held back at the end of the stream
+    fn finish(&mut self) -> io::Result<()> {
+        match self.filter.as_mut().and_then(SentenceFilter::finish) {
+            Some(item) => self.write_item(item),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Removes the PID file when dropped
+struct PidFile(PathBuf);
+
+impl PidFile {
+    fn create(path: PathBuf) -> Option<PidFile> {
+        match fs::write(&path, process::id().to_string()) {
+            Ok(()) => Some(PidFile(path)),
+            Err(e) => {
+                tracing::warn!("could not write pid file {}: {e}", path.display());
+                None
+            }
         }
     }
+}
+
+impl Drop for PidFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.0);
+    }
+}
+
+fn run() -> Result<()> {
+    let cli = Cli::parse();
+
+    let level = match cli.verbose {
+        0 => LevelFilter::INFO,
+        1 => LevelFilter::DEBUG,
+        _ => LevelFilter::TRACE,
+    };
+    let log_file = match &cli.log_file {
+        Some(path) => Some(
+            fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .map_err(|e| anyhow!("could not open log file {}: {e}", path.display()))?,
+        ),
+        None => None,
+    };
+    tracing_subscriber::registry()
+        .with(
+            fmt::layer()
+                .with_writer(io::stderr)
+                .with_ansi(io::stderr().is_terminal()),
+        )
+        .with(log_file.map(|file| fmt::layer().json().with_writer(Mutex::new(file))))
+        .with(level)
+        .init();
+
+    match cli.command {
+        Commands::Connect(args) => connect(*args, cli.verbose),
+        Commands::Sourcetable(args) => print_sourcetable(args),
+    }
+}
+
+fn connect(opt: ConnectArgs, verbose: u8) -> Result<()> {
+    check_position(opt.lat, opt.lon)?;
+
+    let _pid_file = opt.pid_file.clone().and_then(PidFile::create);
+
+    let mut request = Request::new(&opt.url);
+    request.username = opt.username.clone();
+    request.password = opt.password.clone();
+    request.max_redirects = opt.max_redirects;
+    request.redirect_credentials = opt.redirect_credentials;
+    request.headers.push("Transfer-Encoding:".into());
+    request
+        .headers
+        .push(format!("X-SwiftNav-Client-Id: {}", opt.client_id));
+
+    if opt.nmea_header {
+        let cmd = build_command(&opt);
+        request
+            .headers
+            .push(format!("{}: {}", cmd.message.header_name(), cmd));
+    }
 
     if opt.no_eph {
-        headers.append("X-No-Ephemerides-On-Connection: True")?;
+        request
+            .headers
+            .push("X-No-Ephemerides-On-Connection: True".into());
     }
 
-    curl.http_headers(headers)?;
-    curl.useragent("NTRIP ntrip-client/1.0")?;
-    curl.url(&opt.url)?;
+    let mut curl = request.easy()?;
     curl.progress(true)?;
     curl.put(true)?;
     curl.custom_request("GET")?;
-    curl.http_version(HttpVersion::Any)?;
-    curl.http_09_allowed(true)?;
+
+    if let Some(timeout) = opt.connect_timeout {
+        curl.connect_timeout(Duration::from_secs(timeout))?;
+    }
 
     if let (Some(cert), Some(key)) = (&opt.cert, &opt.key) {
         curl.ssl_cert(cert)?;
         curl.ssl_key(key)?;
     }
 
-    if opt.verbose || opt.debug {
+    if verbose > 0 || opt.debug {
         curl.verbose(true)?;
     }
 
@@ -411,24 +831,43 @@ fn run() -> Result<()> {
         })?;
     }
 
-    if let Some(username) = &opt.username {
-        curl.username(username)?;
-    }
-
-    if let Some(password) = &opt.password {
-        curl.password(password)?;
-    }
+    // stop the transfer from the progress callback instead of letting the
+    // signal kill the process with unflushed output
+    ctrlc::set_handler(|| {
+        tracing::info!("shutting down");
+        SHUTDOWN.store(true, Ordering::SeqCst);
+    })?;
 
     let (tx, rx) = flume::bounded::<Vec<u8>>(1);
+    let status = Cell::new(0);
+    let error_body = RefCell::new(Vec::new());
+    let last_recv = Cell::new(None::<Instant>);
+    let read_timed_out = Cell::new(false);
+    let mut redirects = url::RedirectPolicy::new(&opt.url);
+    let output = RefCell::new(Output::new(opt.output_format, opt.nmea_ack));
     let transfer = Rc::new(RefCell::new(curl.transfer()));
 
     transfer.borrow_mut().progress_function({
         let rx = &rx;
         let transfer = Rc::clone(&transfer);
+        let last_recv = &last_recv;
+        let read_timed_out = &read_timed_out;
+        let read_timeout = opt.read_timeout.map(Duration::from_secs);
         move |_dltot, _dlnow, _ultot, _ulnow| {
+            if SHUTDOWN.load(Ordering::SeqCst) || SENDER_FAILED.load(Ordering::SeqCst) {
+                return false;
+            }
+            // curl's low speed limit also counts the sentences we upload, so
+            // track the time since the last received data ourselves. The clock
+            // starts with the response, connecting is up to --connect-timeout
+            let since = last_recv.get().map(|t| t.elapsed());
+            if matches!((read_timeout, since), (Some(t), Some(since)) if since >= t) {
+                read_timed_out.set(true);
+                return false;
+            }
             if !rx.is_empty() {
                 if let Err(e) = transfer.borrow().unpause_read() {
-                    eprintln!("unpause error: {e}");
+                    tracing::error!("unpause error: {e}");
                     return false;
                 }
             }
@@ -436,9 +875,37 @@ fn run() -> Result<()> {
         }
     })?;
 
+    transfer.borrow_mut().header_function(|header| {
+        last_recv.set(Some(Instant::now()));
+        if let Some(code) = status_code(header) {
+            status.set(code);
+        }
+        match redirects.check_header(header) {
+            Ok(()) => true,
+            Err(e) => {
+                tracing::error!("{e}");
+                false
+            }
+        }
+    })?;
+
     transfer.borrow_mut().write_function(|data| {
-        if let Err(e) = io::stdout().write_all(data) {
-            eprintln!("write error: {e}");
+        tracing::trace!("received {} bytes", data.len());
+        last_recv.set(Some(Instant::now()));
+        if status.get() >= 400 {
+            // keep the start of the body for the error message instead of
+            // mixing it into the corrections output
+            let mut body = error_body.borrow_mut();
+            let n = data.len().min(MAX_ERROR_BODY - body.len());
+            body.extend_from_slice(&data[..n]);
+            return Ok(if body.len() < MAX_ERROR_BODY {
+                data.len()
+            } else {
+                0
+            });
+        }
+        if let Err(e) = output.borrow_mut().write(data) {
+            tracing::error!("write error: {e}");
             return Ok(0);
         }
         Ok(data.len())
@@ -452,26 +919,100 @@ fn run() -> Result<()> {
         };
         bytes.extend_from_slice(b"\r\n");
         if let Err(e) = data.write_all(&bytes) {
-            eprintln!("read error: {e}");
+            tracing::error!("read error: {e}");
             return Err(ReadError::Abort);
         }
         Ok(bytes.len())
     })?;
 
+    let serial = match &opt.serial_port {
+        Some(path) => Some(
+            serialport::new(path, opt.serial_baud)
+                .timeout(Duration::from_secs(1))
+                .open()
+                .map_err(|e| anyhow!("could not open serial port {path}: {e}"))?,
+        ),
+        None => None,
+    };
+
     let commands = get_commands(opt.clone())?;
+    let sentences = opt.input_sentence.clone();
+    let period = Duration::from_secs(opt.nmea_period);
     let handle = thread::spawn(move || {
+        for sentence in sentences {
+            tracing::debug!("sending {sentence}");
+            if tx.send(sentence.into_bytes()).is_err() {
+                return Ok(());
+            }
+        }
+        if let Some(port) = serial {
+            let result = forward_serial_gga(io::BufReader::new(port), period, &tx);
+            SENDER_FAILED.store(result.is_err(), Ordering::SeqCst);
+            return result;
+        }
         for cmd in commands {
             if cmd.after > 0 {
                 thread::sleep(Duration::from_secs(cmd.after));
             }
-            if tx.send(cmd.to_bytes()).is_err() {
+            let bytes = cmd.to_bytes();
+            tracing::debug!("sending {}", String::from_utf8_lossy(&bytes));
+            if tx.send(bytes).is_err() {
                 break;
             }
         }
         Ok(())
     });
 
-    transfer.borrow().perform()?;
+    tracing::info!(
+        url = %url::redact_credentials(&opt.url),
+        client_id = %opt.client_id,
+        auth = opt.username.is_some() || opt.password.is_some() || url::has_credentials(&opt.url),
+        "connecting"
+    );
+
+    let result = transfer.borrow().perform();
+
+    // NTRIP 1.0 casters answer "ICY 200 OK", which has no HTTP status
+    if last_recv.get().is_some() {
+        let status = Some(status.get()).filter(|&code| code != 0);
+        tracing::info!(status, "disconnected");
+    }
+
+    output.borrow_mut().finish()?;
+
+    if SHUTDOWN.load(Ordering::SeqCst) {
+        // make sure no partially written message is left in the buffer
+        io::stdout().flush()?;
+        return Ok(());
+    }
+
+    if SENDER_FAILED.load(Ordering::SeqCst) {
+        return handle.join().unwrap();
+    }
+
+    if status.get() >= 400 {
+        let body = error_body.borrow();
+        let body = String::from_utf8_lossy(&body);
+        let body = body.trim();
+        return if body.is_empty() {
+            Err(anyhow!("caster responded with status {}", status.get()).into())
+        } else {
+            Err(anyhow!("caster responded with status {}: {}", status.get(), body).into())
+        };
+    }
+
+    if read_timed_out.get() {
+        let timeout = opt.read_timeout.unwrap_or_default();
+        return Err(anyhow!("read timed out after {timeout}s without data").into());
+    }
+
+    if let (Err(e), Some(timeout)) = (&result, opt.connect_timeout) {
+        if e.is_operation_timedout() {
+            return Err(anyhow!("connection timed out after {timeout}s").into());
+        }
+    }
+
+    result?;
 
     if !handle.is_finished() {
         Ok(())
@@ -486,3 +1027,182 @@ fn main() {
         eprintln!("Error: {e}");
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gga(lat: f64, lon: f64) -> String {
+        let msg = Message::Gga {
+            lat,
+            lon,
+            height: 0.0,
+        };
+        msg.format(DateTime::from(SystemTime::UNIX_EPOCH))
+    }
+
+    #[test]
+    fn gga_latitude_full_precision() {
+        assert!(gga(37.77103777777778, 0.0).contains(",3746.2622667,N,"));
+    }
+
+    #[test]
+    fn gga_whole_degree_longitudes() {
+        assert!(gga(0.0, 0.0).contains(",00000.0000000,E,"));
+        assert!(gga(0.0, 180.0).contains(",18000.0000000,E,"));
+        assert!(gga(0.0, -180.0).contains(",18000.0000000,W,"));
+    }
+    #[test]
+    fn minutes_carry_into_degrees() {
+        assert_eq!(degrees_minutes(37.99999999999, 2), "3800.0000000");
+    }
+    fn connect_args(args: &[&str]) -> ConnectArgs {
+        let args = ["ntripping", "connect"].iter().chain(args);
+        match Cli::try_parse_from(args).unwrap().command {
+            Commands::Connect(args) => *args,
+            Commands::Sourcetable(_) => unreachable!(),
+        }
+    }
+
+    fn input_commands(name: &str, yaml: &str) -> Result<Vec<Command>> {
+        let path = std::env::temp_dir().join(format!("ntripping-{}-{name}.yaml", process::id()));
+        fs::write(&path, yaml).unwrap();
+        let commands = get_commands(connect_args(&["--input", path.to_str().unwrap()]));
+        fs::remove_file(&path).unwrap();
+        Ok(commands?.collect())
+    }
+
+    #[test]
+    fn input_positions_checked() {
+        let gga = |lat: &str, lon: &str| {
+            format!("- gga:\n    lat: {lat}\n    lon: {lon}\n    height: 0\n")
+        };
+        assert_eq!(
+            input_commands("ok", &gga("37.7", "-122.4")).unwrap().len(),
+            1
+        );
+        let err = |name, yaml: &str| input_commands(name, yaml).unwrap_err().to_string();
+        assert_eq!(err("lat", &gga("500", "0")), "Invalid latitude of 500");
+        assert_eq!(
+            err("lon", &gga("0", "-180.5")),
+            "Invalid longitude of -180.5"
+        );
+        assert_eq!(err("nan", &gga(".nan", "0")), "Invalid latitude of NaN");
+    }
+
+    #[test]
+    fn positions_checked() {
+        assert!(check_position(90.0, -180.0).is_ok());
+        assert!(check_position(90.1, 0.0).is_err());
+        assert!(check_position(0.0, 180.1).is_err());
+        assert!(check_position(f64::NAN, 0.0).is_err());
+        assert!(check_position(0.0, f64::INFINITY).is_err());
+    }
+
+    #[test]
+    fn input_sentences_checked() {
+        assert_eq!(
+            parse_sentence("$PSWTCRA,1,2*53").as_deref(),
+            Ok("$PSWTCRA,1,2*53")
+        );
+        assert_eq!(
+            parse_sentence("$PSWTCRA,1,2*54").unwrap_err(),
+            "checksum mismatch, expected 53 but got 54"
+        );
+        for s in ["PSWTCRA,1,2*53", "$PSWTCRA,1,2", "$PSWTCRA,1,2*053"] {
+            assert_eq!(
+                parse_sentence(s).unwrap_err(),
+                "expected $<SENTENCE>*<CHECKSUM>"
+            );
+        }
+
+        let args = connect_args(&["--input-sentence", "$PSWTCRA,1,2*53"]);
+        assert_eq!(args.input_sentence, ["$PSWTCRA,1,2*53"]);
+        let args = [
+            "ntripping",
+            "connect",
+            "--input-sentence",
+            "$PSWTCRA,1,2*54",
+        ];
+        assert!(Cli::try_parse_from(args).is_err());
+    }
+
+    const GGA: &str =
+        "$GPGGA,000000.00,3746.2611998,N,12224.1890955,W,4,12,1.3,-5.55,M,0.0,M,1.7,0078*71";
+    const GNGGA: &str =
+        "$GNGGA,000001.00,3746.2611998,N,12224.1890955,W,4,12,1.3,-5.55,M,0.0,M,1.7,0078*6E";
+
+    /// Runs forward_serial_gga to the end of `input`, returning what was sent
+    fn forward(input: &str, period: Duration) -> (Vec<String>, String) {
+        let (tx, rx) = flume::unbounded();
+        let err = forward_serial_gga(input.as_bytes(), period, &tx).unwrap_err();
+        let sent = rx
+            .try_iter()
+            .map(|s| String::from_utf8(s).unwrap())
+            .collect();
+        (sent, err.to_string())
+    }
+
+    #[test]
+    fn serial_gga_talkers() {
+        let input = format!("{GGA}\r\n$GNRMC,x*00\r\n\u{b5}b binary\r\n{GNGGA}\r\n");
+        let (sent, err) = forward(&input, Duration::ZERO);
+        assert_eq!(sent, [GGA, GNGGA]);
+        assert_eq!(err, "serial port closed");
+    }
+
+    #[test]
+    fn serial_gga_throttled() {
+        let input = format!("{GGA}\r\n{GNGGA}\r\n{GGA}\r\n");
+        assert_eq!(forward(&input, Duration::from_secs(60)).0, [GGA]);
+    }
+
+    #[test]
+    fn serial_gga_bad_checksum() {
+        let bad = GGA.replace("*71", "*70");
+        let input = format!("{bad}\r\n{GNGGA}\r\n");
+        assert_eq!(forward(&input, Duration::from_secs(60)).0, [GNGGA]);
+    }
+
+    #[test]
+    fn serial_gga_overlong_line() {
+        // a GGA at the end of an overlong line is not forwarded
+        let long = format!("{}{GGA}", "x".repeat(MAX_SERIAL_LINE));
+        let input = format!("{long}\r\n{GNGGA}\r\n");
+        assert_eq!(forward(&input, Duration::ZERO).0, [GNGGA]);
+    }
+
+    #[test]
+    fn header_names() {
+        let header = |args: &[&str]| build_command(&connect_args(args)).message.header_name();
+        assert_eq!(header(&[]), "Ntrip-GGA");
+        assert_eq!(header(&["--lat", "10"]), "Ntrip-GGA");
+        assert_eq!(header(&["--area-id", "5"]), "Ntrip-CRA");
+        assert_eq!(header(&["--pos-to-area-id"]), "Ntrip-CRA");
+    }
+
+    fn gga_time(hour: u32, min: u32, sec: u32, nano: u32) -> String {
+        let time = chrono::NaiveDate::from_ymd_opt(2024, 6, 30)
+            .and_then(|d| d.and_hms_nano_opt(hour, min, sec, nano))
+            .unwrap()
+            .and_utc();
+        let msg = Message::Gga {
+            lat: 0.0,
+            lon: 0.0,
+            height: 0.0,
+        };
+        msg.format(time)
+    }
+
+    #[test]
+    fn gga_time_centiseconds() {
+        assert!(gga_time(12, 34, 56, 789_000_000).starts_with("$GPGGA,123456.78,"));
+        assert!(gga_time(0, 0, 0, 0).starts_with("$GPGGA,000000.00,"));
+    }
+
+    #[test]
+    fn gga_time_leap_second() {
+        // chrono represents a leap second as more than 1e9 nanoseconds
+        assert!(gga_time(23, 59, 59, 1_500_000_000).starts_with("$GPGGA,235960.99,"));
+    }
+}
@@ -0,0 +1,82 @@
+use std::fmt;
+
+/// XOR checksum of the characters between `$` and `*`
+pub fn checksum(body: &[u8]) -> u8 {
+    body.iter().fold(0, |sum, b| sum ^ b)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SentenceError {
+    /// Not of the form `$<SENTENCE>*<CHECKSUM>` with a two digit hex checksum
+    Format,
+    Checksum {
+        expected: u8,
+        actual: u8,
+    },
+}
+
+impl fmt::Display for SentenceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SentenceError::Format => write!(f, "expected $<SENTENCE>*<CHECKSUM>"),
+            SentenceError::Checksum { expected, actual } => write!(
+                f,
+                "checksum mismatch, expected {expected:02X} but got {actual:02X}"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for SentenceError {}
+
+/// Check that `sentence`, without its line ending, is a `$...*HH` sentence
+/// with a valid checksum
+pub fn validate(sentence: &str) -> Result<(), SentenceError> {
+    let (body, crc) = sentence
+        .strip_prefix('$')
+        .and_then(|rest| rest.rsplit_once('*'))
+        .ok_or(SentenceError::Format)?;
+    if crc.len() != 2 || !crc.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return Err(SentenceError::Format);
+    }
+    let actual = u8::from_str_radix(crc, 16).map_err(|_| SentenceError::Format)?;
+    let expected = checksum(body.as_bytes());
+    if actual != expected {
+        return Err(SentenceError::Checksum { expected, actual });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checksums() {
+        assert_eq!(checksum(b"PSWTCRA,1,2"), 0x53);
+        assert_eq!(validate("$PSWTCRA,1,2*53"), Ok(()));
+        assert_eq!(
+            validate("$PSWTCRA,1,2*54"),
+            Err(SentenceError::Checksum {
+                expected: 0x53,
+                actual: 0x54
+            })
+        );
+    }
+
+    #[test]
+    fn formats() {
+        for s in [
+            "PSWTCRA,1,2*53",
+            "$PSWTCRA,1,2",
+            "$A*5",
+            "$A*041",
+            "$A*+A",
+            "$A*4G",
+        ] {
+            assert_eq!(validate(s), Err(SentenceError::Format), "{}", s);
+        }
+        assert_eq!(validate("$A*41"), Ok(()));
+        assert_eq!(validate("$A*41\r\n"), Err(SentenceError::Format));
+    }
+}
@@ -0,0 +1,68 @@
+use curl::easy::{Easy, HttpVersion, List};
+
+use crate::version;
+
+/// Redirects followed unless configured otherwise
+pub const DEFAULT_MAX_REDIRECTS: u32 = 3;
+
+/// The settings shared by every request made to a caster.
+///
+/// [`Request::easy`] turns them into a curl handle, which callers then set up
+/// further for their transfer. Redirects should be checked with
+/// [`crate::url::RedirectPolicy`] from the transfer's header callback.
+#[derive(Debug, Clone)]
+pub struct Request {
+    pub url: String,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    pub max_redirects: u32,
+    /// Also send credentials when redirected to another host
+    pub redirect_credentials: bool,
+    /// Request headers sent in addition to `Ntrip-Version`
+    pub headers: Vec<String>,
+}
+
+impl Request {
+    pub fn new(url: impl Into<String>) -> Self {
+        Request {
+            url: url.into(),
+            username: None,
+            password: None,
+            max_redirects: DEFAULT_MAX_REDIRECTS,
+            redirect_credentials: false,
+            headers: Vec::new(),
+        }
+    }
+
+    /// Create a curl handle for the request
+    pub fn easy(&self) -> Result<Easy, curl::Error> {
+        let mut curl = Easy::new();
+
+        let mut headers = List::new();
+        headers.append("Ntrip-Version: Ntrip/2.0")?;
+        for header in &self.headers {
+            headers.append(header)?;
+        }
+
+        curl.http_headers(headers)?;
+        curl.useragent(version::USER_AGENT)?;
+        curl.url(&self.url)?;
+        // NTRIP 1.0 casters answer without an HTTP status line
+        curl.http_version(HttpVersion::Any)?;
+        curl.http_09_allowed(true)?;
+        curl.follow_location(true)?;
+        curl.max_redirections(self.max_redirects)?;
+        // curl only sends credentials to the original host unless told otherwise
+        curl.unrestricted_auth(self.redirect_credentials)?;
+
+        if let Some(username) = &self.username {
+            curl.username(username)?;
+        }
+
+        if let Some(password) = &self.password {
+            curl.password(password)?;
+        }
+
+        Ok(curl)
+    }
+}
@@ -0,0 +1,191 @@
+use std::fmt;
+
+/// First byte of every RTCM3 frame
+pub const PREAMBLE: u8 = 0xD3;
+
+const HEADER_LEN: usize = 3;
+const CRC_LEN: usize = 3;
+
+/// CRC-24Q, the checksum that ends each RTCM3 frame
+pub fn crc24q(data: &[u8]) -> u32 {
+    let mut crc = 0u32;
+    for &byte in data {
+        crc ^= u32::from(byte) << 16;
+        for _ in 0..8 {
+            crc <<= 1;
+            if crc & 0x100_0000 != 0 {
+                crc ^= 0x186_4CFB;
+            }
+        }
+    }
+    crc & 0xFF_FFFF
+}
+
+/// Longest payload a frame can carry
+pub const MAX_PAYLOAD_LEN: usize = 1023;
+
+/// Wrap `payload` in an RTCM3 frame for testing.
+///
+/// The payload is prefixed with `message_type` in the 12 bit message number
+/// field, followed by four zero bits, and the frame gets a valid length and
+/// CRC. The result is not a standards compliant message, it is only meant for
+/// exercising code that handles RTCM3 streams, such as [`Rtcm3Framer`].
+///
+/// # Panics
+///
+/// If `message_type` does not fit in 12 bits, or the payload plus the two
+/// message type bytes is longer than [`MAX_PAYLOAD_LEN`].
+pub fn fake_frame(message_type: u16, payload: &[u8]) -> Vec<u8> {
+    assert!(
+        message_type < 1 << 12,
+        "message type {} out of range",
+        message_type
+    );
+    let len = payload.len() + 2;
+    assert!(len <= MAX_PAYLOAD_LEN, "payload of {} bytes too long", len);
+
+    let mut frame = Vec::with_capacity(HEADER_LEN + len + CRC_LEN);
+    frame.extend_from_slice(&[PREAMBLE, (len >> 8) as u8, len as u8]);
+    frame.extend_from_slice(&(message_type << 4).to_be_bytes());
+    frame.extend_from_slice(payload);
+    let crc = crc24q(&frame);
+    frame.extend_from_slice(&crc.to_be_bytes()[1..]);
+    frame
+}
+
+/// A frame that failed the CRC check
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InvalidFrame {
+    /// Position of the frame in the stream, counting from 1
+    pub number: u64,
+}
+
+impl fmt::Display for InvalidFrame {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "RTCM3 frame {} failed the CRC check", self.number)
+    }
+}
+
+impl std::error::Error for InvalidFrame {}
+
+/// Reassembles RTCM3 frames from a byte stream.
+///
+/// Data is pushed in as it arrives and whole frames are returned once they
+/// are complete. Bytes outside of a frame are skipped. After a CRC failure
+/// the search resumes at the byte following the bad frame's preamble, so a
+/// corrupted length does not swallow the frames after it.
+#[derive(Debug, Default)]
+pub struct Rtcm3Framer {
+    buf: Vec<u8>,
+    frames: u64,
+    invalid: u64,
+}
+
+impl Rtcm3Framer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Feed the next chunk of the stream, returning the frames it completed
+    pub fn push(&mut self, data: &[u8]) -> Vec<Result<Vec<u8>, InvalidFrame>> {
+        self.buf.extend_from_slice(data);
+        let mut frames = Vec::new();
+        loop {
+            match self.buf.iter().position(|&b| b == PREAMBLE) {
+                Some(start) => drop(self.buf.drain(..start)),
+                None => {
+                    self.buf.clear();
+                    break;
+                }
+            }
+            if self.buf.len() < HEADER_LEN {
+                break;
+            }
+            // the six bits after the preamble are reserved and always zero
+            if self.buf[1] & 0xFC != 0 {
+                self.buf.remove(0);
+                continue;
+            }
+            let len = usize::from(self.buf[1]) << 8 | usize::from(self.buf[2]);
+            let total = HEADER_LEN + len + CRC_LEN;
+            if self.buf.len() < total {
+                break;
+            }
+            self.frames += 1;
+            let (body, crc) = self.buf[..total].split_at(HEADER_LEN + len);
+            let crc = u32::from(crc[0]) << 16 | u32::from(crc[1]) << 8 | u32::from(crc[2]);
+            if crc24q(body) == crc {
+                frames.push(Ok(self.buf.drain(..total).collect()));
+            } else {
+                self.invalid += 1;
+                frames.push(Err(InvalidFrame {
+                    number: self.frames,
+                }));
+                self.buf.remove(0);
+            }
+        }
+        frames
+    }
+
+    /// Number of frames that failed the CRC check so far
+    pub fn invalid_frames(&self) -> u64 {
+        self.invalid
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn crc() {
+        assert_eq!(crc24q(b"123456789"), 0xCD_E703);
+    }
+
+    #[test]
+    fn fake_frame_round_trip() {
+        let frame = fake_frame(1005, b"$GPGGA,payload*00");
+        assert_eq!(&frame[..5], &[PREAMBLE, 0x00, 0x13, 0x3E, 0xD0]);
+
+        let mut framer = Rtcm3Framer::new();
+        let (last, rest) = frame.split_last().unwrap();
+        for &byte in rest {
+            assert_eq!(framer.push(&[byte]), vec![]);
+        }
+        assert_eq!(framer.push(&[*last]), vec![Ok(frame.clone())]);
+    }
+
+    #[test]
+    fn resync_after_bad_crc() {
+        let good = fake_frame(1005, b"good");
+        let mut bad = fake_frame(1005, b"bad");
+        let end = bad.len() - 1;
+        bad[end] ^= 1;
+
+        let mut framer = Rtcm3Framer::new();
+        let data = [b"\x01\x02".as_ref(), &bad, &good].concat();
+        assert_eq!(
+            framer.push(&data),
+            vec![Err(InvalidFrame { number: 1 }), Ok(good)]
+        );
+        assert_eq!(framer.invalid_frames(), 1);
+    }
+
+    #[test]
+    fn largest_payload() {
+        let frame = fake_frame(4095, &[0; MAX_PAYLOAD_LEN - 2]);
+        assert_eq!(frame.len(), 3 + MAX_PAYLOAD_LEN + 3);
+    }
+
+    #[test]
+    #[should_panic(expected = "message type")]
+    fn message_type_out_of_range() {
+        fake_frame(4096, b"");
+    }
+
+    #[test]
+    #[should_panic(expected = "too long")]
+    fn payload_too_long() {
+        fake_frame(1005, &[0; MAX_PAYLOAD_LEN - 1]);
+    }
+}
@@ -0,0 +1,457 @@
+use std::fmt;
+use std::str::{FromStr, Split};
+
+use crate::request::Request;
+use crate::url::RedirectPolicy;
+
+/// Content type NTRIP 2.0 casters use for sourcetable responses
+pub const CONTENT_TYPE: &str = "gnss/sourcetable";
+
+/// Status line NTRIP 1.0 casters send instead of an HTTP response
+const STATUS_V1: &[u8] = b"SOURCETABLE 200 OK";
+
+const END: &[u8] = b"ENDSOURCETABLE";
+
+/// How much of an error response body is kept for the error message
+const MAX_ERROR_BODY: usize = 1024;
+
+/// The records of an NTRIP 2.0 sourcetable
+#[derive(Debug, Clone, Default, PartialEq, serde::Serialize)]
+pub struct Sourcetable {
+    pub streams: Vec<StrRecord>,
+    pub networks: Vec<NetRecord>,
+    pub casters: Vec<CasRecord>,
+    /// Records that could not be parsed and were left out
+    #[serde(skip)]
+    pub skipped: Vec<ParseError>,
+}
+
+/// A `STR` record, describing a single data stream (mountpoint)
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct StrRecord {
+    pub mountpoint: String,
+    pub identifier: String,
+    pub format: String,
+    pub format_details: String,
+    pub carrier: u8,
+    pub nav_system: String,
+    pub network: String,
+    pub country: String,
+    pub lat: f64,
+    pub lon: f64,
+    pub nmea: bool,
+    pub solution: u8,
+    pub generator: String,
+    pub compr_encr: String,
+    pub authentication: String,
+    pub fee: bool,
+    pub bitrate: Option<u32>,
+    pub misc: String,
+}
+
+/// A `NET` record, describing a network of streams
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct NetRecord {
+    pub identifier: String,
+    pub operator: String,
+    pub authentication: String,
+    pub fee: bool,
+    pub web_net: String,
+    pub web_str: String,
+    pub web_reg: String,
+    pub misc: String,
+}
+
+/// A `CAS` record, describing a caster
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct CasRecord {
+    pub host: String,
+    pub port: u16,
+    pub identifier: String,
+    pub operator: String,
+    pub nmea: bool,
+    pub country: String,
+    pub lat: f64,
+    pub lon: f64,
+    pub fallback_host: String,
+    pub fallback_port: Option<u16>,
+    pub misc: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseError {
+    MissingField {
+        line: usize,
+        field: &'static str,
+    },
+    InvalidField {
+        line: usize,
+        field: &'static str,
+        value: String,
+    },
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseError::MissingField { line, field } => {
+                write!(f, "line {line}: missing {field} field")
+            }
+            ParseError::InvalidField { line, field, value } => {
+                write!(f, "line {line}: invalid {field} field {value:?}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+#[derive(Debug)]
+pub enum Error {
+    Curl(curl::Error),
+    /// The caster responded with something other than a sourcetable, e.g.
+    /// because the URL named a mountpoint rather than the caster root
+    NotASourcetable(Option<String>),
+    /// The caster responded with an HTTP error, with the start of the body
+    Status(u32, String),
+    /// A redirect was refused by [`RedirectPolicy`]
+    Redirect(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Curl(e) => e.fmt(f),
+            Error::NotASourcetable(Some(content_type)) => {
+                write!(f, "not a sourcetable (content type {content_type})")
+            }
+            Error::NotASourcetable(None) => write!(f, "not a sourcetable (no content type)"),
+            Error::Status(status, body) if body.is_empty() => {
+                write!(f, "caster responded with status {status}")
+            }
+            Error::Status(status, body) => {
+                write!(f, "caster responded with status {status}: {body}")
+            }
+            Error::Redirect(e) => e.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Curl(e) => Some(e),
+            Error::NotASourcetable(_) | Error::Status(..) | Error::Redirect(_) => None,
+        }
+    }
+}
+
+impl From<curl::Error> for Error {
+    fn from(e: curl::Error) -> Self {
+        Error::Curl(e)
+    }
+}
+
+/// Request and parse the sourcetable of the caster at `url`.
+///
+/// `url` should be the caster root (e.g. `na.skylark.swiftnav.com:2101/`),
+/// not the URL of a mountpoint. The response body is read until
+/// `ENDSOURCETABLE` is seen. Both NTRIP 2.0 responses and NTRIP 1.0
+/// `SOURCETABLE 200 OK` replies are accepted. Redirects are followed as
+/// they are when connecting to a mountpoint.
+pub fn fetch(url: &str) -> Result<Sourcetable, Error> {
+    let mut curl = Request::new(url).easy()?;
+
+    let mut body = Vec::new();
+    let mut redirects = RedirectPolicy::new(url);
+    let mut refused = None;
+    let result = {
+        let mut transfer = curl.transfer();
+        transfer.header_function(|header| match redirects.check_header(header) {
+            Ok(()) => true,
+            Err(e) => {
+                refused = Some(e);
+                false
+            }
+        })?;
+        transfer.write_function(|data| {
+            let start = body.len().saturating_sub(END.len());
+            body.extend_from_slice(data);
+            if body[start..].windows(END.len()).any(|w| w == END) {
+                // stop here rather than waiting for the caster to hang up
+                return Ok(0);
+            }
+            Ok(data.len())
+        })?;
+        transfer.perform()
+    };
+
+    if let Some(e) = refused {
+        return Err(Error::Redirect(e));
+    }
+    match result {
+        Err(e) if !e.is_write_error() => return Err(e.into()),
+        _ => {}
+    }
+
+    let status = curl.response_code()?;
+    if status >= 400 {
+        body.truncate(MAX_ERROR_BODY);
+        let body = String::from_utf8_lossy(&body).trim().to_owned();
+        return Err(Error::Status(status, body));
+    }
+
+    let content_type = curl.content_type()?.map(str::to_owned);
+    let is_sourcetable = match &content_type {
+        Some(t) => t.split(';').next() == Some(CONTENT_TYPE),
+        None => body.starts_with(STATUS_V1),
+    };
+    if !is_sourcetable {
+        return Err(Error::NotASourcetable(content_type));
+    }
+
+    Ok(parse_sourcetable(&String::from_utf8_lossy(&body)))
+}
+
+/// Parse the body of a sourcetable response.
+///
+/// Parsing stops at `ENDSOURCETABLE`. Lines that are not `STR`, `NET` or
+/// `CAS` records (such as the status line and headers sent by NTRIP 1.0
+/// casters) are ignored. Malformed records are left out of the table and
+/// reported in [`Sourcetable::skipped`], so one bad record does not hide the
+/// rest.
+pub fn parse_sourcetable(text: &str) -> Sourcetable {
+    let mut table = Sourcetable::default();
+    for (idx, line) in text.lines().enumerate() {
+        if line == "ENDSOURCETABLE" {
+            break;
+        }
+        let mut fields = Fields::new(line, idx + 1);
+        let result = match fields.inner.next() {
+            Some("STR") => StrRecord::parse(fields).map(|r| table.streams.push(r)),
+            Some("NET") => NetRecord::parse(fields).map(|r| table.networks.push(r)),
+            Some("CAS") => CasRecord::parse(fields).map(|r| table.casters.push(r)),
+            _ => Ok(()),
+        };
+        if let Err(e) = result {
+            table.skipped.push(e);
+        }
+    }
+    table
+}
+
+impl FromStr for Sourcetable {
+    type Err = std::convert::Infallible;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(parse_sourcetable(s))
+    }
+}
+
+impl StrRecord {
+    fn parse(mut fields: Fields<'_>) -> Result<Self, ParseError> {
+        Ok(StrRecord {
+            mountpoint: fields.string("mountpoint")?,
+            identifier: fields.string("identifier")?,
+            format: fields.string("format")?,
+            format_details: fields.string("format-details")?,
+            carrier: fields.parse("carrier")?,
+            nav_system: fields.string("nav-system")?,
+            network: fields.string("network")?,
+            country: fields.string("country")?,
+            lat: fields.parse("latitude")?,
+            lon: fields.parse("longitude")?,
+            nmea: fields.flag("nmea", "1", "0")?,
+            solution: fields.parse("solution")?,
+            generator: fields.string("generator")?,
+            compr_encr: fields.string("compr-encryp")?,
+            authentication: fields.string("authentication")?,
+            fee: fields.flag("fee", "Y", "N")?,
+            bitrate: fields.optional("bitrate")?,
+            misc: fields.rest(),
+        })
+    }
+}
+
+impl NetRecord {
+    fn parse(mut fields: Fields<'_>) -> Result<Self, ParseError> {
+        Ok(NetRecord {
+            identifier: fields.string("identifier")?,
+            operator: fields.string("operator")?,
+            authentication: fields.string("authentication")?,
+            fee: fields.flag("fee", "Y", "N")?,
+            web_net: fields.string("web-net")?,
+            web_str: fields.string("web-str")?,
+            web_reg: fields.string("web-reg")?,
+            misc: fields.rest(),
+        })
+    }
+}
+
+impl CasRecord {
+    fn parse(mut fields: Fields<'_>) -> Result<Self, ParseError> {
+        Ok(CasRecord {
+            host: fields.string("host")?,
+            port: fields.parse("port")?,
+            identifier: fields.string("identifier")?,
+            operator: fields.string("operator")?,
+            nmea: fields.flag("nmea", "1", "0")?,
+            country: fields.string("country")?,
+            lat: fields.parse("latitude")?,
+            lon: fields.parse("longitude")?,
+            fallback_host: fields.string("fallback host")?,
+            fallback_port: fields.optional("fallback port")?,
+            misc: fields.rest(),
+        })
+    }
+}
+
+struct Fields<'a> {
+    inner: Split<'a, char>,
+    line: usize,
+}
+
+impl<'a> Fields<'a> {
+    fn new(line: &'a str, number: usize) -> Self {
+        Fields {
+            inner: line.split(';'),
+            line: number,
+        }
+    }
+
+    fn next(&mut self, field: &'static str) -> Result<&'a str, ParseError> {
+        self.inner.next().ok_or(ParseError::MissingField {
+            line: self.line,
+            field,
+        })
+    }
+
+    fn invalid(&self, field: &'static str, value: &str) -> ParseError {
+        ParseError::InvalidField {
+            line: self.line,
+            field,
+            value: value.to_owned(),
+        }
+    }
+
+    fn string(&mut self, field: &'static str) -> Result<String, ParseError> {
+        self.next(field).map(str::to_owned)
+    }
+
+    fn parse<T: FromStr>(&mut self, field: &'static str) -> Result<T, ParseError> {
+        let value = self.next(field)?;
+        value.trim().parse().map_err(|_| self.invalid(field, value))
+    }
+
+    fn optional<T: FromStr>(&mut self, field: &'static str) -> Result<Option<T>, ParseError> {
+        let value = self.next(field)?;
+        if value.trim().is_empty() {
+            return Ok(None);
+        }
+        value
+            .trim()
+            .parse()
+            .map(Some)
+            .map_err(|_| self.invalid(field, value))
+    }
+
+    fn flag(&mut self, field: &'static str, yes: &str, no: &str) -> Result<bool, ParseError> {
+        let value = self.next(field)?;
+        match value.trim() {
+            v if v == yes => Ok(true),
+            v if v == no => Ok(false),
+            _ => Err(self.invalid(field, value)),
+        }
+    }
+
+    /// The misc field is free text and may itself contain `;`
+    fn rest(self) -> String {
+        self.inner.collect::<Vec<_>>().join(";")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const STR: &str = "STR;CRS;San Francisco;RTCM 3.2;1004(1),1012(1);2;GPS+GLO;SKYLARK;USA;37.77;-122.40;1;1;sbc;none;B;N;9600;misc";
+    const NET: &str = "NET;SKYLARK;Swift Navigation;B;N;https://swiftnav.com;https://swiftnav.com/str;https://swiftnav.com/reg;none";
+    const CAS: &str = "CAS;na.skylark.swiftnav.com;2101;Skylark;Swift Navigation;0;USA;37.77;-122.40;fallback.swiftnav.com;2102;misc";
+
+    #[test]
+    fn records() {
+        let table = parse_sourcetable(&[STR, NET, CAS, "ENDSOURCETABLE"].join("\n"));
+        assert!(table.skipped.is_empty());
+
+        let stream = &table.streams[0];
+        assert_eq!(stream.mountpoint, "CRS");
+        assert_eq!(stream.format_details, "1004(1),1012(1)");
+        assert_eq!(stream.carrier, 2);
+        assert_eq!((stream.lat, stream.lon), (37.77, -122.40));
+        assert!(stream.nmea);
+        assert!(!stream.fee);
+        assert_eq!(stream.bitrate, Some(9600));
+        assert_eq!(stream.misc, "misc");
+
+        let network = &table.networks[0];
+        assert_eq!(network.identifier, "SKYLARK");
+        assert_eq!(network.web_reg, "https://swiftnav.com/reg");
+        assert_eq!(network.misc, "none");
+
+        let caster = &table.casters[0];
+        assert_eq!(caster.host, "na.skylark.swiftnav.com");
+        assert_eq!(caster.port, 2101);
+        assert!(!caster.nmea);
+        assert_eq!(caster.fallback_host, "fallback.swiftnav.com");
+        assert_eq!(caster.fallback_port, Some(2102));
+    }
+
+    #[test]
+    fn misc_with_separator() {
+        let table = parse_sourcetable(&STR.replace(";misc", ";a;b;c"));
+        assert_eq!(table.streams[0].misc, "a;b;c");
+    }
+
+    #[test]
+    fn empty_bitrate() {
+        let table = parse_sourcetable(&STR.replace(";9600;", ";;"));
+        assert_eq!(table.streams[0].bitrate, None);
+    }
+
+    #[test]
+    fn crlf_line_endings() {
+        let text = format!("SOURCETABLE 200 OK\r\n\r\n{STR}\r\n{CAS}\r\nENDSOURCETABLE\r\n");
+        let table = parse_sourcetable(&text);
+        assert!(table.skipped.is_empty());
+        assert_eq!(table.streams[0].misc, "misc");
+        assert_eq!(table.casters[0].misc, "misc");
+    }
+
+    #[test]
+    fn stops_at_end() {
+        let table = parse_sourcetable(&[STR, "ENDSOURCETABLE", STR].join("\n"));
+        assert_eq!(table.streams.len(), 1);
+    }
+
+    #[test]
+    fn skips_malformed_records() {
+        let bad = STR.replace(";2;GPS", ";;GPS");
+        let table = parse_sourcetable(&[STR, &bad, "STR;short", NET].join("\n"));
+        assert_eq!(table.streams.len(), 1);
+        assert_eq!(table.networks.len(), 1);
+        assert_eq!(
+            table.skipped,
+            vec![
+                ParseError::InvalidField {
+                    line: 2,
+                    field: "carrier",
+                    value: String::new(),
+                },
+                ParseError::MissingField {
+                    line: 3,
+                    field: "identifier",
+                },
+            ]
+        );
+    }
+}
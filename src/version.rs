@@ -0,0 +1,7 @@
+/// The version of this crate
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// The user agent sent to casters.
+///
+/// NTRIP requires the user agent to start with `NTRIP`.
+pub const USER_AGENT: &str = "NTRIP ntrip-client/1.0";